@@ -0,0 +1,122 @@
+// geoip enrichment: looks up country/city/asn for a public ip over the
+// outbound http client, with a ttl cache so repeat hits from the same
+// client dont re-query the upstream on every page load
+use crate::http_client;
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+// free, no-key endpoint; swap for a paid provider if this ever needs auth
+const ENDPOINT_BASE: &str = "http://ip-api.com/json/";
+// how long a cached lookup stays fresh before we re-query the upstream
+const CACHE_TTL: Duration = Duration::from_secs(300);
+
+#[derive(Clone)]
+pub struct GeoInfo {
+    pub country: String,
+    pub city: String,
+    pub asn: String,
+}
+
+impl GeoInfo {
+    fn unknown() -> Self {
+        Self {
+            country: "unknown".to_string(),
+            city: "unknown".to_string(),
+            asn: "unknown".to_string(),
+        }
+    }
+}
+
+pub type GeoCache = Arc<Mutex<HashMap<String, (GeoInfo, Instant)>>>;
+
+// looks up geo info for an ip, preferring a fresh cache entry and
+// degrading to "unknown" whenever the upstream cant be reached in time
+//
+// `ip` comes straight off client-controlled headers (fly-client-ip,
+// x-forwarded-for, x-real-ip), so it's parsed as an IpAddr before it ever
+// touches the outbound url or the cache key: that rejects anything with
+// stray whitespace/control bytes, and is_non_routable keeps us from
+// spending an upstream call on loopback/private/link-local addresses that
+// would never resolve to a real location anyway
+pub fn lookup(cache: &GeoCache, ip: &str) -> GeoInfo {
+    let addr: IpAddr = match ip.parse() {
+        Ok(addr) => addr,
+        Err(_) => return GeoInfo::unknown(),
+    };
+
+    if is_non_routable(addr) {
+        return GeoInfo::unknown();
+    }
+
+    // the parsed, re-rendered form, so the cache key and url segment are
+    // always exactly what IpAddr::to_string produces, never raw header text
+    let key = addr.to_string();
+
+    if let Ok(map) = cache.lock() {
+        if let Some((info, fetched_at)) = map.get(&key) {
+            if fetched_at.elapsed() < CACHE_TTL {
+                return info.clone();
+            }
+        }
+    }
+
+    let info = fetch_geo_info(&key).unwrap_or_else(GeoInfo::unknown);
+
+    if let Ok(mut map) = cache.lock() {
+        map.insert(key, (info.clone(), Instant::now()));
+    }
+
+    info
+}
+
+// loopback/private/link-local/etc addresses never resolve to a useful
+// location, so skip the upstream call entirely for them
+fn is_non_routable(addr: IpAddr) -> bool {
+    match addr {
+        IpAddr::V4(v4) => {
+            v4.is_private()
+                || v4.is_loopback()
+                || v4.is_link_local()
+                || v4.is_broadcast()
+                || v4.is_documentation()
+                || v4.is_unspecified()
+        }
+        IpAddr::V6(v6) => {
+            v6.is_loopback()
+                || v6.is_unspecified()
+                // link-local fe80::/10
+                || (v6.segments()[0] & 0xffc0) == 0xfe80
+                // unique local fc00::/7 (Ipv6Addr::is_unique_local is still nightly-only)
+                || (v6.segments()[0] & 0xfe00) == 0xfc00
+        }
+    }
+}
+
+fn fetch_geo_info(ip: &str) -> Option<GeoInfo> {
+    let url = format!("{}{}", ENDPOINT_BASE, ip);
+    let response = http_client::fetch(&url).ok()?;
+    if response.status != 200 {
+        return None;
+    }
+    let body = String::from_utf8_lossy(&response.body);
+
+    Some(GeoInfo {
+        country: extract_json_field(&body, "country").unwrap_or_else(|| "unknown".to_string()),
+        city: extract_json_field(&body, "city").unwrap_or_else(|| "unknown".to_string()),
+        asn: extract_json_field(&body, "as").unwrap_or_else(|| "unknown".to_string()),
+    })
+}
+
+// pulls out `"key":"value"` from a flat json object without pulling in a
+// json crate, same spirit as the rest of this codebase's hand rolled parsing
+fn extract_json_field(body: &str, key: &str) -> Option<String> {
+    let needle = format!("\"{}\":\"", key);
+    let start = body.find(&needle)? + needle.len();
+    let rest = &body[start..];
+    let end = rest.find('"')?;
+    Some(rest[..end].to_string())
+}
@@ -0,0 +1,348 @@
+// small blocking http client used to reach out to the geoip upstream.
+// mirrors a hardened fetcher: capped body size, total timeout, bounded
+// redirects.
+//
+// scope decision: plain http only, no tls. this tree has no Cargo.toml and
+// vendors no crates, and hand-rolling tls by hand isn't a reasonable
+// substitute, so https is out of scope for this client rather than a
+// secretly-half-implemented feature. geoip::ENDPOINT_BASE is http, so the
+// only way this bites today is a 3xx redirect pointing at an https
+// location: fetch() surfaces that as FetchError::UnsupportedScheme instead
+// of silently following it, and the caller (geoip::lookup) degrades to
+// "unknown" the same way it does for any other fetch failure. revisit if
+// this ever needs to talk to an https-only upstream.
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::{Duration, Instant},
+};
+
+// hard cap on how much response body we'll ever buffer in memory
+const MAX_BODY_BYTES: usize = 64 * 1024 * 1024;
+// total wall clock budget for the whole fetch, redirects included
+const TOTAL_TIMEOUT: Duration = Duration::from_secs(5);
+// dont chase a redirect chain forever
+const MAX_REDIRECTS: u8 = 5;
+
+pub struct Response {
+    pub status: u16,
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub enum FetchError {
+    InvalidUrl,
+    // deliberately not implemented, see the module doc comment above:
+    // this is the initial url's scheme, or a redirect Location's scheme,
+    // whenever it isn't "http"
+    UnsupportedScheme(String),
+    Io(std::io::Error),
+    Timeout,
+    TooManyRedirects,
+    BodyTooLarge,
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FetchError::InvalidUrl => write!(f, "invalid url"),
+            FetchError::UnsupportedScheme(s) => write!(f, "unsupported scheme: {}", s),
+            FetchError::Io(e) => write!(f, "io error: {}", e),
+            FetchError::Timeout => write!(f, "request timed out"),
+            FetchError::TooManyRedirects => write!(f, "too many redirects"),
+            FetchError::BodyTooLarge => write!(f, "response body too large"),
+        }
+    }
+}
+
+impl From<std::io::Error> for FetchError {
+    fn from(e: std::io::Error) -> Self {
+        FetchError::Io(e)
+    }
+}
+
+// just enough of a url to dial a host and send a request line
+struct ParsedUrl {
+    scheme: String,
+    host: String,
+    port: u16,
+    path: String,
+}
+
+fn parse_url(url: &str) -> Result<ParsedUrl, FetchError> {
+    let (scheme, rest) = url.split_once("://").ok_or(FetchError::InvalidUrl)?;
+    let scheme = scheme.to_lowercase();
+
+    let (authority, path) = match rest.find('/') {
+        Some(pos) => (&rest[..pos], &rest[pos..]),
+        None => (rest, "/"),
+    };
+    if authority.is_empty() {
+        return Err(FetchError::InvalidUrl);
+    }
+
+    let default_port = match scheme.as_str() {
+        "http" => 80,
+        "https" => 443,
+        _ => return Err(FetchError::UnsupportedScheme(scheme)),
+    };
+
+    let (host, port) = match authority.split_once(':') {
+        Some((h, p)) => (h, p.parse().map_err(|_| FetchError::InvalidUrl)?),
+        None => (authority, default_port),
+    };
+
+    Ok(ParsedUrl {
+        scheme,
+        host: host.to_string(),
+        port,
+        path: path.to_string(),
+    })
+}
+
+// resolves a Location header against the url we just fetched, handling
+// both absolute redirects and bare paths
+fn resolve_location(base: &ParsedUrl, location: &str) -> String {
+    if location.contains("://") {
+        location.to_string()
+    } else {
+        format!("{}://{}:{}{}", base.scheme, base.host, base.port, location)
+    }
+}
+
+// fetches a single url, following up to MAX_REDIRECTS redirects, within a
+// shared total timeout. http only: an https url, or a redirect into one,
+// returns FetchError::UnsupportedScheme rather than being followed (see
+// the module doc comment for why).
+pub fn fetch(url: &str) -> Result<Response, FetchError> {
+    let deadline = Instant::now() + TOTAL_TIMEOUT;
+    let mut current = url.to_string();
+
+    for _ in 0..=MAX_REDIRECTS {
+        let remaining = deadline
+            .checked_duration_since(Instant::now())
+            .ok_or(FetchError::Timeout)?;
+
+        let parsed = parse_url(&current)?;
+        if parsed.scheme != "http" {
+            return Err(FetchError::UnsupportedScheme(parsed.scheme));
+        }
+
+        let response = fetch_once(&parsed, remaining)?;
+
+        if is_redirect(response.status) {
+            if let Some(location) = response.headers.get("location") {
+                current = resolve_location(&parsed, location);
+                continue;
+            }
+        }
+
+        return Ok(response);
+    }
+
+    Err(FetchError::TooManyRedirects)
+}
+
+fn is_redirect(status: u16) -> bool {
+    matches!(status, 301 | 302 | 303 | 307 | 308)
+}
+
+fn fetch_once(parsed: &ParsedUrl, budget: Duration) -> Result<Response, FetchError> {
+    let deadline = Instant::now() + budget;
+
+    // bound the connect by the budget too; a plain connect can block for the
+    // os default (minutes) on an unreachable host
+    let addr = (parsed.host.as_str(), parsed.port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or(FetchError::InvalidUrl)?;
+    let mut stream = TcpStream::connect_timeout(&addr, budget)?;
+    stream.set_read_timeout(Some(budget))?;
+    stream.set_write_timeout(Some(budget))?;
+
+    write!(
+        stream,
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: iplookup-rs\r\nConnection: close\r\nAccept: */*\r\n\r\n",
+        parsed.path, parsed.host
+    )?;
+    stream.flush()?;
+
+    read_response(&mut stream, deadline)
+}
+
+fn read_response(stream: &mut TcpStream, deadline: Instant) -> Result<Response, FetchError> {
+    let mut buf = [0u8; 8192];
+    let mut raw = Vec::new();
+    let mut header_end = None;
+
+    // read until we've seen the blank line that ends the headers
+    while header_end.is_none() {
+        if Instant::now() >= deadline {
+            return Err(FetchError::Timeout);
+        }
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        raw.extend_from_slice(&buf[..n]);
+        if raw.len() > MAX_BODY_BYTES {
+            return Err(FetchError::BodyTooLarge);
+        }
+        header_end = find_header_end(&raw);
+    }
+
+    let header_end = header_end.ok_or(FetchError::Io(std::io::Error::new(
+        std::io::ErrorKind::UnexpectedEof,
+        "connection closed before headers completed",
+    )))?;
+
+    let head = String::from_utf8_lossy(&raw[..header_end]);
+    let mut lines = head.split("\r\n");
+    let status_line = lines.next().unwrap_or("");
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse().ok())
+        .ok_or(FetchError::InvalidUrl)?;
+
+    let mut headers = HashMap::new();
+    for line in lines {
+        if let Some((k, v)) = line.split_once(':') {
+            headers.insert(k.trim().to_lowercase(), v.trim().to_string());
+        }
+    }
+
+    let body_start = header_end + 4;
+    let chunked = headers
+        .get("transfer-encoding")
+        .map(|v| v.eq_ignore_ascii_case("chunked"))
+        .unwrap_or(false);
+
+    let body = if chunked {
+        read_chunked_body(stream, &raw[body_start..], deadline)?
+    } else {
+        let content_length = headers
+            .get("content-length")
+            .and_then(|v| v.parse::<usize>().ok());
+        read_sized_body(stream, &raw[body_start..], content_length, deadline)?
+    };
+
+    Ok(Response {
+        status,
+        headers,
+        body,
+    })
+}
+
+fn find_header_end(buf: &[u8]) -> Option<usize> {
+    buf.windows(4).position(|w| w == b"\r\n\r\n")
+}
+
+fn read_sized_body(
+    stream: &mut TcpStream,
+    already_read: &[u8],
+    content_length: Option<usize>,
+    deadline: Instant,
+) -> Result<Vec<u8>, FetchError> {
+    let mut body = already_read.to_vec();
+    let mut buf = [0u8; 8192];
+
+    loop {
+        if let Some(len) = content_length {
+            if body.len() >= len {
+                body.truncate(len);
+                break;
+            }
+        }
+        if body.len() > MAX_BODY_BYTES {
+            return Err(FetchError::BodyTooLarge);
+        }
+        if Instant::now() >= deadline {
+            return Err(FetchError::Timeout);
+        }
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            // no content-length and the peer closed: thats the end of body
+            break;
+        }
+        body.extend_from_slice(&buf[..n]);
+    }
+
+    if body.len() > MAX_BODY_BYTES {
+        return Err(FetchError::BodyTooLarge);
+    }
+
+    Ok(body)
+}
+
+// minimal chunked-transfer-encoding reader: size line, chunk bytes, repeat
+// until a zero-size chunk closes the stream
+fn read_chunked_body(
+    stream: &mut TcpStream,
+    already_read: &[u8],
+    deadline: Instant,
+) -> Result<Vec<u8>, FetchError> {
+    let mut pending = already_read.to_vec();
+    let mut out = Vec::new();
+    let mut buf = [0u8; 8192];
+
+    loop {
+        // make sure we have a full chunk-size line buffered
+        while find_header_end_single(&pending).is_none() {
+            if Instant::now() >= deadline {
+                return Err(FetchError::Timeout);
+            }
+            let n = stream.read(&mut buf)?;
+            if n == 0 {
+                return Err(FetchError::Io(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "connection closed mid chunk size",
+                )));
+            }
+            pending.extend_from_slice(&buf[..n]);
+        }
+
+        let line_end = find_header_end_single(&pending).unwrap();
+        let size_line = String::from_utf8_lossy(&pending[..line_end]).to_string();
+        let size_str = size_line.split(';').next().unwrap_or("").trim();
+        let size = usize::from_str_radix(size_str, 16).map_err(|_| FetchError::InvalidUrl)?;
+        pending.drain(..line_end + 2);
+
+        if size == 0 {
+            break;
+        }
+
+        while pending.len() < size + 2 {
+            if out.len() + pending.len() > MAX_BODY_BYTES {
+                return Err(FetchError::BodyTooLarge);
+            }
+            if Instant::now() >= deadline {
+                return Err(FetchError::Timeout);
+            }
+            let n = stream.read(&mut buf)?;
+            if n == 0 {
+                return Err(FetchError::Io(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "connection closed mid chunk body",
+                )));
+            }
+            pending.extend_from_slice(&buf[..n]);
+        }
+
+        out.extend_from_slice(&pending[..size]);
+        pending.drain(..size + 2);
+
+        if out.len() > MAX_BODY_BYTES {
+            return Err(FetchError::BodyTooLarge);
+        }
+    }
+
+    Ok(out)
+}
+
+fn find_header_end_single(buf: &[u8]) -> Option<usize> {
+    buf.windows(2).position(|w| w == b"\r\n")
+}
@@ -1,52 +1,85 @@
+mod geoip;
+mod http_client;
+
+use geoip::GeoCache;
 use std::{
     collections::HashMap,
     io::{Read, Write},
-    net::{TcpListener, TcpStream},
-    sync::{mpsc, Arc, Mutex},
+    net::{IpAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex,
+    },
     thread,
     time::{Duration, Instant},
 };
 
-type RateLimiter = Arc<Mutex<HashMap<String, Vec<Instant>>>>;
+// per ip, we only keep the theoretical arrival time (tat) the gcra needs
+// plus a call counter so we know when its time for a sweep
+struct RateLimiterState {
+    tats: HashMap<String, Instant>,
+    calls_since_sweep: u64,
+}
+
+type RateLimiter = Arc<Mutex<RateLimiterState>>;
 
 struct IpInfo {
     public_ip: String,
     peer_ip: String,
     forwarded: String,
     user_agent: String,
+    geo: geoip::GeoInfo,
+}
+
+// boxed closure to run as a job
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+// messages sent down the job channel
+// terminate tells a worker to stop its recv loop instead of running a job
+enum Message {
+    NewJob(Job),
+    Terminate,
 }
 
 // simple fixed size thread pool
 struct ThreadPool {
     // sender side of the job channel
-    tx: mpsc::Sender<Job>,
+    tx: mpsc::Sender<Message>,
+    // join handles so drop can wait for workers to actually finish
+    workers: Vec<thread::JoinHandle<()>>,
 }
 
-// boxed closure to run as a job
-type Job = Box<dyn FnOnce() + Send + 'static>;
-
 impl ThreadPool {
     // creates a new pool with N threads
     fn new(size: usize) -> Self {
         // creates channel for jobs
-        let (tx, rx) = mpsc::channel::<Job>();
+        let (tx, rx) = mpsc::channel::<Message>();
         // wrap receiver so many threads can block on it
         let rx = Arc::new(Mutex::new(rx));
-        // spawn N threads that pull jobs and run them
+        // spawn N threads that pull jobs and run them, keeping the handles
+        let mut workers = Vec::with_capacity(size);
         for _ in 0..size {
             // clone the shared receiver for this thread
             let rx = Arc::clone(&rx);
             // start the worker loop
-            thread::spawn(move || {
-                // keep receiving jobs until sender is dropped
-                while let Ok(job) = rx.lock().unwrap().recv() {
-                    // run the job
-                    job();
+            let handle = thread::spawn(move || {
+                // keep receiving until the channel closes or we get terminate
+                loop {
+                    // the lock guard is dropped at the end of this statement, so
+                    // other workers can take jobs while this one runs its job
+                    let msg = rx.lock().unwrap().recv();
+                    match msg {
+                        // run the job
+                        Ok(Message::NewJob(job)) => job(),
+                        // finish the current job (already done above) and exit
+                        Ok(Message::Terminate) | Err(_) => break,
+                    }
                 }
             });
+            workers.push(handle);
         }
         // return the pool
-        Self { tx }
+        Self { tx, workers }
     }
 
     // schedules a job to run on the pool
@@ -55,7 +88,21 @@ impl ThreadPool {
         F: FnOnce() + Send + 'static,
     {
         // send the job ignoring errors if pool is shutting down
-        let _ = self.tx.send(Box::new(f));
+        let _ = self.tx.send(Message::NewJob(Box::new(f)));
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        // send one terminate per worker so each of them breaks out of recv
+        for _ in &self.workers {
+            let _ = self.tx.send(Message::Terminate);
+        }
+
+        // join every worker, letting in-flight jobs finish before we return
+        for handle in self.workers.drain(..) {
+            let _ = handle.join();
+        }
     }
 }
 
@@ -68,7 +115,13 @@ fn main() {
     // creates a shared rate limiter using arc and mutex
     // arc lets multiple threads share the same data
     // mutex ensures only one thread can modify it at a time
-    let limiter: RateLimiter = Arc::new(Mutex::new(HashMap::new()));
+    let limiter: RateLimiter = Arc::new(Mutex::new(RateLimiterState {
+        tats: HashMap::new(),
+        calls_since_sweep: 0,
+    }));
+
+    // caches geoip lookups per ip so repeat visits dont re-query the upstream
+    let geo_cache: GeoCache = Arc::new(Mutex::new(HashMap::new()));
 
     // creates a small thread pool sized to cpu * 4
     // unwrap_or uses 8 if detection fails
@@ -78,76 +131,201 @@ fn main() {
             .unwrap_or(8),
     );
 
-    // incoming waits for someone to connect
-    // flatten removes the Option and Result that come along
-    // this way we keep only the real connections
-    for stream in listener.incoming().flatten() {
+    // flipped by the /shutdown route once a trusted caller hits it
+    // main polls this instead of blocking forever in accept so the pool
+    // (and its graceful Drop) actually gets a chance to run
+    let shutdown = Arc::new(AtomicBool::new(false));
+
+    // nonblocking so the accept loop below can check shutdown between polls
+    listener
+        .set_nonblocking(true)
+        .expect("failed to set nonblocking");
+
+    // incoming would block forever on accept, so we poll manually instead
+    // and bail out once /shutdown flips the flag
+    while !shutdown.load(Ordering::SeqCst) {
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            // no connection ready yet, back off briefly and check shutdown again
+            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                thread::sleep(Duration::from_millis(50));
+                continue;
+            }
+            // anything else isnt recoverable for this accept, just retry
+            Err(_) => continue,
+        };
         // clones the arc reference for this task
         // arc::clone only increments the reference count, doesnt copy the data
         let limiter = Arc::clone(&limiter);
+        let shutdown = Arc::clone(&shutdown);
+        let geo_cache = Arc::clone(&geo_cache);
         // sends the work to the pool
         pool.execute(move || {
             // handles the connection inside a worker thread
-            handle_connection(stream, limiter);
+            handle_connection(stream, limiter, shutdown, geo_cache);
         });
     }
+
+    // dropping the pool here broadcasts terminate and joins every worker
+}
+
+// caps how big a single request (headers + body) is allowed to get
+const MAX_REQUEST_SIZE: usize = 16384;
+
+// a parse-level failure, distinct from a plain io error, so the caller can
+// decide whether a response (and a forced close) is owed to the client
+enum ReadRequestError {
+    TooLarge,
+    Io,
+}
+
+impl From<std::io::Error> for ReadRequestError {
+    fn from(_: std::io::Error) -> Self {
+        ReadRequestError::Io
+    }
 }
 
-// actually reads the full request from the stream
-// this is critical because peek doesnt consume data and can cause deadlocks
-fn read_request(stream: &mut TcpStream) -> Result<String, std::io::Error> {
-    let mut buf = [0; 8192]; // bigger buffer for safety
-    let mut request = Vec::new();
-    let mut content_length = 0;
-    let mut body_start = 0;
+// pulls exactly one request (headers + content-length body) out of `buffer`,
+// reading more off `stream` as needed, and leaves any pipelined bytes from a
+// following request untouched in `buffer` for the next call to pick up.
+// returns Ok(None) on a clean close with nothing pending.
+fn read_request(
+    stream: &mut TcpStream,
+    buffer: &mut Vec<u8>,
+) -> Result<Option<String>, ReadRequestError> {
+    let mut buf = [0; 8192];
 
-    // read until we have complete headers
     loop {
+        if let Some(header_end) = find_header_end(buffer) {
+            let body_start = header_end + 4;
+            let content_length = parse_content_length(&buffer[..header_end]);
+
+            // full request (headers + body) is buffered, split it off
+            if buffer.len() >= body_start + content_length {
+                let total = body_start + content_length;
+                let request_bytes: Vec<u8> = buffer.drain(..total).collect();
+                return Ok(Some(String::from_utf8_lossy(&request_bytes).to_string()));
+            }
+        }
+
+        if buffer.len() > MAX_REQUEST_SIZE {
+            return Err(ReadRequestError::TooLarge);
+        }
+
         let n = stream.read(&mut buf)?;
         if n == 0 {
-            break;
+            // clean close: fine if nothing was pending, a broken request otherwise
+            return if buffer.is_empty() {
+                Ok(None)
+            } else {
+                Err(ReadRequestError::Io)
+            };
         }
-        request.extend_from_slice(&buf[..n]);
-
-        let req_str = String::from_utf8_lossy(&request);
+        buffer.extend_from_slice(&buf[..n]);
+    }
+}
 
-        // look for end of headers
-        if let Some(pos) = req_str.find("\r\n\r\n") {
-            body_start = pos + 4;
+fn find_header_end(buf: &[u8]) -> Option<usize> {
+    buf.windows(4).position(|w| w == b"\r\n\r\n")
+}
 
-            // extract content-length if present
-            for line in req_str.lines() {
-                if line.to_lowercase().starts_with("content-length:") {
-                    if let Some(len_str) = line.split(':').nth(1) {
-                        content_length = len_str.trim().parse().unwrap_or(0);
-                    }
-                    break;
-                }
+fn parse_content_length(headers: &[u8]) -> usize {
+    let headers = String::from_utf8_lossy(headers);
+    for line in headers.lines() {
+        if line.to_lowercase().starts_with("content-length:") {
+            if let Some(len_str) = line.split(':').nth(1) {
+                return len_str.trim().parse().unwrap_or(0);
             }
+        }
+    }
+    0
+}
 
-            let body_received = request.len() - body_start;
+// true if the client expects the connection to stay open after this
+// response: an explicit `Connection` header wins, otherwise it falls back
+// to the protocol default (keep-alive for 1.1, close for 1.0)
+fn wants_keep_alive(req: &str) -> bool {
+    let first_line = req.lines().next().unwrap_or("");
+    let defaults_to_keep_alive = first_line.contains("HTTP/1.1");
 
-            // if we got all the body or theres no body, were done
-            if body_received >= content_length {
-                break;
+    for line in req.lines().skip(1) {
+        if line.is_empty() {
+            break;
+        }
+        if let Some((k, v)) = line.split_once(':') {
+            if k.trim().eq_ignore_ascii_case("connection") {
+                return v.trim().eq_ignore_ascii_case("keep-alive");
             }
         }
+    }
 
-        // avoid infinite loop on malformed requests
-        if request.len() > 16384 {
+    defaults_to_keep_alive
+}
+
+// true if the client asked for json via the Accept header
+fn wants_json_response(req: &str) -> bool {
+    for line in req.lines().skip(1) {
+        if line.is_empty() {
             break;
         }
+        if let Some((k, v)) = line.split_once(':') {
+            if k.trim().eq_ignore_ascii_case("accept") {
+                return v.to_lowercase().contains("application/json");
+            }
+        }
     }
+    false
+}
+
+// hand rolled serializer so curl and monitoring scripts get structured
+// output without pulling in a json crate
+fn ip_info_to_json(info: &IpInfo) -> String {
+    format!(
+        r#"{{"public_ip":"{}","peer_ip":"{}","forwarded":"{}","user_agent":"{}","geo":{{"country":"{}","city":"{}","asn":"{}"}}}}"#,
+        escape_json_string(&info.public_ip),
+        escape_json_string(&info.peer_ip),
+        escape_json_string(&info.forwarded),
+        escape_json_string(&info.user_agent),
+        escape_json_string(&info.geo.country),
+        escape_json_string(&info.geo.city),
+        escape_json_string(&info.geo.asn),
+    )
+}
 
-    Ok(String::from_utf8_lossy(&request).to_string())
+// escapes quotes, backslashes and control chars so untrusted header values
+// (user-agent, x-forwarded-for, ...) cant break out of the json string
+fn escape_json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
 }
 
-fn handle_connection(mut stream: TcpStream, limiter: RateLimiter) {
-    // sets read and write timeouts to 5 seconds
-    // this prevents connections from hanging forever
-    // ok() ignores if it fails, nbg
-    stream.set_read_timeout(Some(Duration::from_secs(5))).ok();
-    stream.set_write_timeout(Some(Duration::from_secs(5))).ok();
+// how long a kept-alive connection may sit idle waiting for the next request
+const KEEP_ALIVE_IDLE_TIMEOUT: Duration = Duration::from_secs(5);
+// caps requests served per connection so one client cant hog a worker forever
+const MAX_REQUESTS_PER_CONNECTION: u32 = 100;
+
+fn handle_connection(
+    mut stream: TcpStream,
+    limiter: RateLimiter,
+    shutdown: Arc<AtomicBool>,
+    geo_cache: GeoCache,
+) {
+    // this doubles as the keep-alive idle timeout: a socket read timeout
+    // applies to each read call on its own, so it bounds the gap between
+    // requests, not the whole connection
+    stream.set_read_timeout(Some(KEEP_ALIVE_IDLE_TIMEOUT)).ok();
+    stream.set_write_timeout(Some(KEEP_ALIVE_IDLE_TIMEOUT)).ok();
 
     // gets the peer ip address
     // this is the direct ip of the connection (usually from proxy)
@@ -156,60 +334,125 @@ fn handle_connection(mut stream: TcpStream, limiter: RateLimiter) {
         Err(_) => return, // if we cant get the ip, just drop the connection
     };
 
-    // checks if this ip has exceeded the rate limit
-    // if so, sends a 429 response and closes the connection
-    if !check_rate_limit(&limiter, &peer_ip) {
-        send_response(
-            &mut stream,
-            "429 Too Many Requests",
-            "text/plain",
-            "rate limit exceeded",
-        );
-        return;
-    }
+    // holds bytes read off the socket that belong to a pipelined request
+    // not yet processed, carried across loop iterations
+    let mut buffer = Vec::new();
 
-    // actually reads the full request now instead of just peeking
-    // this is crucial to avoid the browser hanging on post requests
-    let req = match read_request(&mut stream) {
-        Ok(r) => r,
-        Err(_) => return,
-    };
+    for request_index in 0..MAX_REQUESTS_PER_CONNECTION {
+        // checks if this ip has exceeded the rate limit
+        // if so, sends a 429 response with a retry-after hint and closes
+        if let Err(retry_after) = check_rate_limit(&limiter, &peer_ip) {
+            send_rate_limited_response(&mut stream, retry_after);
+            return;
+        }
 
-    // grabs the first request line safely
-    // this avoids false positives from searching the whole buffer
-    let first_line = req.lines().next().unwrap_or("");
+        // actually reads one full request now instead of just peeking
+        // this is crucial to avoid the browser hanging on post requests
+        let req = match read_request(&mut stream, &mut buffer) {
+            Ok(Some(r)) => r,
+            Ok(None) => return, // client closed cleanly between requests
+            Err(ReadRequestError::TooLarge) => {
+                send_response(
+                    &mut stream,
+                    "400 Bad Request",
+                    "text/plain",
+                    "request too large",
+                    false,
+                );
+                return;
+            }
+            Err(ReadRequestError::Io) => return,
+        };
+
+        // grabs the first request line safely
+        // this avoids false positives from searching the whole buffer
+        let first_line = req.lines().next().unwrap_or("");
+        // the last request this loop will serve before hitting the
+        // per-connection cap: dont advertise reuse we're about to revoke
+        let is_last_allowed_request = request_index + 1 == MAX_REQUESTS_PER_CONNECTION;
+        let mut keep_alive = wants_keep_alive(&req) && !is_last_allowed_request;
+
+        // method and path, parsed out of "GET /path HTTP/1.1"
+        let mut first_line_parts = first_line.split_whitespace();
+        let method = first_line_parts.next().unwrap_or("");
+        let path = first_line_parts.next().unwrap_or("");
+
+        // route logic for javascript-free experience, plus a json escape
+        // hatch for curl and monitoring scripts
+        let (status, body, ctype) = if method == "GET" && path == "/shutdown" {
+            // only a caller reaching us directly from loopback may stop the server
+            // this is a blunt allowlist, not real auth, so keep it to operators
+            let is_loopback = match peer_ip.parse::<IpAddr>() {
+                Ok(addr) => addr.is_loopback(),
+                Err(_) => false,
+            };
+            if is_loopback {
+                // flips the flag the main accept loop polls on every wakeup
+                shutdown.store(true, Ordering::SeqCst);
+                // a deterministic stop shouldn't wait on this client to close
+                // its socket before ThreadPool::drop gets to join this worker
+                keep_alive = false;
+                ("200 OK", "shutting down".to_string(), "text/plain")
+            } else {
+                ("403 Forbidden", "forbidden".to_string(), "text/plain")
+            }
+        } else if (method == "GET" || method == "POST") && (path == "/" || path == "/json") {
+            // /json always wants json, otherwise it comes down to the Accept header
+            let wants_json = path == "/json" || wants_json_response(&req);
+
+            if wants_json {
+                // same ip resolution POST / does, just serialized instead of spliced into html
+                let ip_info = extract_ip_info(&req, &peer_ip, &geo_cache);
+                ("200 OK", ip_info_to_json(&ip_info), "application/json")
+            } else if method == "GET" {
+                // serves initial page, no ip data
+                let html_template = include_str!("index.html");
+                let final_html = html_template.replace("{ip_info_placeholder}", "");
+                ("200 OK", final_html, "text/html")
+            } else {
+                // get data and updates page with ip
+                let ip_info = extract_ip_info(&req, &peer_ip, &geo_cache);
+                let html_template = include_str!("index.html");
+                let ip_info_html = format!(
+                    r#"
+                    <div class="info-line"><span class="label">public_ip:</span> <span class="value">{}</span></div>
+                    <div class="info-line"><span class="label">peer_ip:</span> <span class="value">{}</span></div>
+                    <div class="info-line"><span class="label">forwarded:</span> <span class="value">{}</span></div>
+                    <div class="info-line"><span class="label">user_agent:</span> <span class="value">{}</span></div>
+                    <div class="info-line"><span class="label">country:</span> <span class="value">{}</span></div>
+                    <div class="info-line"><span class="label">city:</span> <span class="value">{}</span></div>
+                    <div class="info-line"><span class="label">asn:</span> <span class="value">{}</span></div>
+                    <div class="info-line"><span class="cursor">_</span></div>
+                    "#,
+                    ip_info.public_ip,
+                    ip_info.peer_ip,
+                    ip_info.forwarded,
+                    ip_info.user_agent,
+                    ip_info.geo.country,
+                    ip_info.geo.city,
+                    ip_info.geo.asn
+                );
+                let final_html = html_template.replace("{ip_info_placeholder}", &ip_info_html);
+                ("200 OK", final_html, "text/html")
+            }
+        } else {
+            ("404 Not Found", "not found".to_string(), "text/plain")
+        };
 
-    // route logic for javascript-free experience
-    let (status, body, ctype) = if first_line.starts_with("GET / ") {
-        // serves initial page, no ip data
-        let html_template = include_str!("index.html");
-        let final_html = html_template.replace("{ip_info_placeholder}", "");
-        ("200 OK", final_html, "text/html")
-    } else if first_line.starts_with("POST / ") {
-        // get data and updates page with ip
-        let ip_info = extract_ip_info(&req, &peer_ip);
-        let html_template = include_str!("index.html");
-        let ip_info_html = format!(
-            r#"
-            <div class="info-line"><span class="label">public_ip:</span> <span class="value">{}</span></div>
-            <div class="info-line"><span class="label">peer_ip:</span> <span class="value">{}</span></div>
-            <div class="info-line"><span class="label">forwarded:</span> <span class="value">{}</span></div>
-            <div class="info-line"><span class="label">user_agent:</span> <span class="value">{}</span></div>
-            <div class="info-line"><span class="cursor">_</span></div>
-            "#,
-            ip_info.public_ip, ip_info.peer_ip, ip_info.forwarded, ip_info.user_agent
-        );
-        let final_html = html_template.replace("{ip_info_placeholder}", &ip_info_html);
-        ("200 OK", final_html, "text/html")
-    } else {
-        ("404 Not Found", "not found".to_string(), "text/plain")
-    };
+        // sends the http response to the client
+        send_response(&mut stream, status, ctype, &body, keep_alive);
 
-    // sends the http response to the client
-    send_response(&mut stream, status, ctype, &body);
+        if !keep_alive {
+            return;
+        }
+    }
+
+    // the per-connection request cap: the last allowed request was already
+    // answered with Connection: close and returned above, so the connection
+    // is dropped here at the latest
 }
 
-fn extract_ip_info(req: &str, peer_ip: &str) -> IpInfo {
+fn extract_ip_info(req: &str, peer_ip: &str, geo_cache: &GeoCache) -> IpInfo {
     // picks only the headers we care about while scanning
     let mut fly = None; // fly-client-ip value if present
     let mut xff = None; // x-forwarded-for full list if present
@@ -255,53 +498,103 @@ fn extract_ip_info(req: &str, peer_ip: &str) -> IpInfo {
     // takes user agent or unknown
     let user_agent = ua.unwrap_or_else(|| "unknown".to_string());
 
+    // enriches the public ip with country/city/asn, cached per ip with a ttl
+    let geo = geoip::lookup(geo_cache, &public_ip);
+
     IpInfo {
         public_ip,
         peer_ip: peer_ip.to_string(),
         forwarded,
         user_agent,
+        geo,
     }
 }
 
-fn check_rate_limit(limiter: &RateLimiter, ip: &str) -> bool {
+// generic cell rate algorithm (gcra): each ip only needs one timestamp,
+// the theoretical arrival time (tat), instead of a growing list we retain
+// every request. returns Ok(()) if allowed, or Err(retry_after) if not.
+fn check_rate_limit(limiter: &RateLimiter, ip: &str) -> Result<(), Duration> {
     // tries to lock the mutex
     // if poisoned just allow the request
-    let mut map = match limiter.lock() {
-        Ok(m) => m,
-        Err(_) => return true,
+    let mut state = match limiter.lock() {
+        Ok(s) => s,
+        Err(_) => return Ok(()),
     };
 
     // captures current time
     let now = Instant::now();
-    // 1 minute window
+    // 1 minute window: 30 requests per minute per ip at a steady rate, and an
+    // ip with no recent requests may send a burst of up to 30 at once
     let window = Duration::from_secs(60);
-    // max 30 requests per minute per ip
-    let max_requests = 30;
+    let max_requests = 30u32;
+
+    // emission interval: how often one request is allowed at steady state
+    let emission_interval = window / max_requests;
+    // burst tolerance: how far ahead of now the tat is allowed to sit
+    let tau = emission_interval * (max_requests - 1);
+
+    // every so often, sweep out ips whose tat has long since passed so the
+    // map doesnt grow forever with one-off clients
+    state.calls_since_sweep += 1;
+    if state.calls_since_sweep >= 1024 {
+        state.calls_since_sweep = 0;
+        state
+            .tats
+            .retain(|_, &mut tat| now.duration_since(tat) < tau);
+    }
 
-    // gets or creates the timestamp vector for this ip
-    let timestamps = map.entry(ip.to_owned()).or_default();
+    // a missing key means this ip has never been seen, so treat its tat as now
+    let tat = state.tats.get(ip).copied().unwrap_or(now);
 
-    // removes old timestamps outside the window
-    timestamps.retain(|&t| now.duration_since(t) < window);
+    // earliest moment this request would have been allowed
+    let allow_at = tat.checked_sub(tau).unwrap_or(tat);
 
-    // if already at the limit, deny the request
-    if timestamps.len() >= max_requests {
-        return false;
+    if now < allow_at {
+        // still inside the burst+rate window, reject with how long to wait
+        return Err(allow_at - now);
     }
 
-    // adds current timestamp and allow the request
-    timestamps.push(now);
-    true
+    // advance the tat: either from the existing tat or from now if it had
+    // already fallen behind, then charge it one emission interval
+    let new_tat = std::cmp::max(tat, now) + emission_interval;
+    state.tats.insert(ip.to_owned(), new_tat);
+
+    Ok(())
 }
 
-fn send_response(stream: &mut TcpStream, status: &str, ctype: &str, body: &str) {
+fn send_rate_limited_response(stream: &mut TcpStream, retry_after: Duration) {
+    // round up so we never tell a client to retry before its slot opens
+    let retry_secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    let body = "rate limit exceeded";
+    let _ = write!(
+        stream,
+        "HTTP/1.1 429 Too Many Requests\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nRetry-After: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        retry_secs,
+        body
+    );
+    let _ = stream.flush();
+}
+
+fn send_response(stream: &mut TcpStream, status: &str, ctype: &str, body: &str, keep_alive: bool) {
     // writes the http response directly without building a big string
+    let connection_headers = if keep_alive {
+        format!(
+            "Connection: keep-alive\r\nKeep-Alive: timeout={}, max={}\r\n",
+            KEEP_ALIVE_IDLE_TIMEOUT.as_secs(),
+            MAX_REQUESTS_PER_CONNECTION
+        )
+    } else {
+        "Connection: close\r\n".to_string()
+    };
+
     let _ = write!(
         stream,
-        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n{}\r\n{}",
         status,
         ctype,
         body.len(),
+        connection_headers,
         body
     );
     // flush ensures all data is sent immediately